@@ -0,0 +1,252 @@
+//! Adaptive (AIMD) rate adjustment, for when the right limit isn't known
+//! up front or varies over time (e.g. a remote API that starts
+//! returning `429`s under load).
+
+use std::{
+    future::Future,
+    num::NonZeroU32,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures_timer::Delay;
+use ratelimit_meter::{algorithms::Algorithm, DirectRateLimiter, NonConformance};
+
+/// Feedback about how a guarded operation turned out, reported back to
+/// an [`AdaptiveRatelimit`] so it can tune its permitted rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The operation completed successfully; the limit may be nudged
+    /// up.
+    Success,
+    /// The operation was rejected or overloaded downstream; the limit
+    /// is backed off.
+    Overload,
+}
+
+struct Inner<A: Algorithm<Instant>>
+where
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    limiter: DirectRateLimiter<A>,
+    rate: u32,
+    min: u32,
+    max: u32,
+    increase_step: u32,
+    backoff_factor: f64,
+    window: Duration,
+    last_adjustment: Instant,
+}
+
+impl<A: Algorithm<Instant>> Inner<A>
+where
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    fn set_rate(&mut self, rate: u32) {
+        self.rate = rate;
+        self.limiter = DirectRateLimiter::<A>::per_second(NonZeroU32::new(rate).unwrap());
+    }
+}
+
+/// An AIMD-tuned rate limit: it starts at `min` requests/second and
+/// additively increases towards `max` on [`Outcome::Success`] reports,
+/// multiplicatively backing off towards `min` on [`Outcome::Overload`]
+/// reports.
+///
+/// Cloning an `AdaptiveRatelimit` is cheap and gives a handle that
+/// shares the same tuned rate, so admission (via
+/// [`acquire`](AdaptiveRatelimit::acquire)) and outcome reporting (via
+/// [`report`](AdaptiveRatelimit::report)) can happen from different
+/// tasks.
+pub struct AdaptiveRatelimit<A: Algorithm<Instant>>
+where
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    inner: Arc<Mutex<Inner<A>>>,
+}
+
+impl<A: Algorithm<Instant>> Clone for AdaptiveRatelimit<A>
+where
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    fn clone(&self) -> Self {
+        AdaptiveRatelimit {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<A: Algorithm<Instant>> AdaptiveRatelimit<A>
+where
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    /// Creates a new adaptive limit, starting at `min` requests/second,
+    /// clamped to `[min, max]` as outcomes are reported. `increase_step`
+    /// is the (roughly) additive increase applied per success, scaled
+    /// down as the rate grows; `backoff_factor` (e.g. `0.5`) is the
+    /// multiplicative factor applied per overload. At most one
+    /// adjustment is applied per `window`, to avoid oscillating on a
+    /// burst of reports.
+    pub fn new(
+        min: NonZeroU32,
+        max: NonZeroU32,
+        increase_step: u32,
+        backoff_factor: f64,
+        window: Duration,
+    ) -> Self {
+        AdaptiveRatelimit {
+            inner: Arc::new(Mutex::new(Inner {
+                limiter: DirectRateLimiter::<A>::per_second(min),
+                rate: min.get(),
+                min: min.get(),
+                max: max.get(),
+                increase_step,
+                backoff_factor,
+                window,
+                last_adjustment: Instant::now() - window,
+            })),
+        }
+    }
+
+    /// Reports how a guarded operation turned out. At most one
+    /// adjustment is applied per configured window; reports arriving
+    /// within an already-adjusted window are ignored.
+    pub fn report(&self, outcome: Outcome) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.last_adjustment.elapsed() < inner.window {
+            return;
+        }
+        let new_rate = match outcome {
+            Outcome::Success => {
+                let step = ((inner.increase_step as f64) / (inner.rate.max(1) as f64))
+                    .ceil()
+                    .max(1.0) as u32;
+                inner.rate.saturating_add(step).min(inner.max)
+            }
+            Outcome::Overload => {
+                ((inner.rate as f64 * inner.backoff_factor) as u32).max(inner.min)
+            }
+        };
+        inner.last_adjustment = Instant::now();
+        if new_rate != inner.rate {
+            inner.set_rate(new_rate);
+        }
+    }
+
+    /// Waits until the currently-tuned limit admits a cell.
+    pub fn acquire(&self) -> Acquire<A> {
+        Acquire {
+            inner: self.inner.clone(),
+            delay: Box::pin(Delay::new(Default::default())),
+            first_time: true,
+        }
+    }
+}
+
+/// Future returned by [`AdaptiveRatelimit::acquire`].
+pub struct Acquire<A: Algorithm<Instant>>
+where
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    inner: Arc<Mutex<Inner<A>>>,
+    delay: Pin<Box<Delay>>,
+    first_time: bool,
+}
+
+impl<A: Algorithm<Instant>> Future for Acquire<A>
+where
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let Acquire {
+            inner,
+            delay,
+            first_time,
+        } = &mut *self;
+        crate::poll_check_delay(delay, first_time, cx, |delay| {
+            let mut inner = inner.lock().unwrap();
+            match inner.limiter.check() {
+                Ok(()) => Ok(()),
+                Err(nc) => {
+                    delay.reset(nc.earliest_possible());
+                    Err(())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use ratelimit_meter::LeakyBucket;
+
+    fn rate(limiter: &AdaptiveRatelimit<LeakyBucket>) -> u32 {
+        limiter.inner.lock().unwrap().rate
+    }
+
+    #[test]
+    fn increases_on_success_and_backs_off_on_overload() {
+        let limiter = AdaptiveRatelimit::<LeakyBucket>::new(
+            NonZeroU32::new(2).unwrap(),
+            NonZeroU32::new(100).unwrap(),
+            10,
+            0.5,
+            Duration::from_secs(0),
+        );
+        assert_eq!(rate(&limiter), 2);
+
+        limiter.report(Outcome::Success);
+        let bumped = rate(&limiter);
+        assert!(bumped > 2, "expected the rate to increase, got {bumped}");
+
+        limiter.report(Outcome::Overload);
+        let backed_off = rate(&limiter);
+        assert!(
+            backed_off < bumped && backed_off >= 2,
+            "expected the rate to back off but stay clamped to the minimum, got {backed_off}"
+        );
+    }
+
+    #[test]
+    fn clamps_to_max_and_min() {
+        let limiter = AdaptiveRatelimit::<LeakyBucket>::new(
+            NonZeroU32::new(2).unwrap(),
+            NonZeroU32::new(5).unwrap(),
+            1000,
+            0.5,
+            Duration::from_secs(0),
+        );
+        for _ in 0..10 {
+            limiter.report(Outcome::Success);
+        }
+        assert_eq!(rate(&limiter), 5);
+
+        for _ in 0..10 {
+            limiter.report(Outcome::Overload);
+        }
+        assert_eq!(rate(&limiter), 2);
+    }
+
+    #[test]
+    fn acquire_admits_a_cell_concurrently_with_reporting() {
+        block_on(async {
+            let limiter = AdaptiveRatelimit::<LeakyBucket>::new(
+                NonZeroU32::new(1000).unwrap(),
+                NonZeroU32::new(1000).unwrap(),
+                0,
+                0.5,
+                Duration::from_secs(0),
+            );
+            let reporter = limiter.clone();
+            let (_, ()) = futures::join!(limiter.acquire(), async {
+                reporter.report(Outcome::Success);
+            });
+        });
+    }
+}
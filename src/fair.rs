@@ -0,0 +1,232 @@
+//! A fair, FIFO-ordered variant of [`Ratelimit`](crate::Ratelimit).
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Instant,
+};
+
+use futures_timer::Delay;
+use ratelimit_meter::{algorithms::Algorithm, clock::Clock, DirectRateLimiter, NonConformance};
+
+/// A shared FIFO wait-list that [`FairRatelimit`] futures register on,
+/// so that whichever of them has been waiting longest is the one
+/// granted access when the limiter frees up, instead of whichever one
+/// happens to poll the limiter next.
+#[derive(Clone, Default)]
+pub struct FairQueue(Arc<Mutex<VecDeque<Waker>>>);
+
+impl FairQueue {
+    /// Creates a new, empty wait-list.
+    pub fn new() -> Self {
+        FairQueue::default()
+    }
+
+    fn enqueue(&self, waker: Waker) {
+        self.0.lock().unwrap().push_back(waker);
+    }
+
+    /// Replaces `old`'s entry in the queue with `new`, in place, so a
+    /// future that's re-polled with a different waker doesn't lose its
+    /// position by being pushed to the back.
+    fn update(&self, old: &Waker, new: Waker) {
+        let mut queue = self.0.lock().unwrap();
+        if let Some(pos) = queue.iter().position(|w| w.will_wake(old)) {
+            queue[pos] = new;
+        } else {
+            queue.push_back(new);
+        }
+    }
+
+    fn is_head(&self, waker: &Waker) -> bool {
+        match self.0.lock().unwrap().front() {
+            Some(front) => front.will_wake(waker),
+            None => true,
+        }
+    }
+
+    fn pop_and_wake_next(&self) {
+        let mut queue = self.0.lock().unwrap();
+        queue.pop_front();
+        if let Some(next) = queue.front() {
+            next.wake_by_ref();
+        }
+    }
+
+    fn remove(&self, waker: &Waker) {
+        let mut queue = self.0.lock().unwrap();
+        if let Some(pos) = queue.iter().position(|w| w.will_wake(waker)) {
+            queue.remove(pos);
+            if pos == 0 {
+                if let Some(next) = queue.front() {
+                    next.wake_by_ref();
+                }
+            }
+        }
+    }
+}
+
+/// The rate-limiter as a future, queued fairly behind any other
+/// [`FairRatelimit`] futures sharing the same [`FairQueue`].
+///
+/// Where [`Ratelimit`](crate::Ratelimit) lets every waiting future race
+/// to recheck the limiter on its own schedule (so a late arrival can
+/// win ahead of one that has been waiting longer), `FairRatelimit`
+/// futures only attempt the limiter once they reach the head of the
+/// shared queue, and hand off to the next waiter as soon as they
+/// succeed or are dropped.
+pub struct FairRatelimit<'a, A: Algorithm<Instant>, C: Clock<Instant = Instant>>
+where
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    delay: Pin<Box<Delay>>,
+    limiter: &'a mut DirectRateLimiter<A, C>,
+    queue: FairQueue,
+    waker: Option<Waker>,
+}
+
+impl<'a, A: Algorithm<Instant>, C: Clock<Instant = Instant>> FairRatelimit<'a, A, C>
+where
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    /// Creates a new future that joins `queue` and resolves successfully
+    /// once it reaches the head of the queue and the rate limiter allows
+    /// it through.
+    pub fn new(limiter: &'a mut DirectRateLimiter<A, C>, queue: FairQueue) -> Self {
+        FairRatelimit {
+            delay: Box::pin(Delay::new(Default::default())),
+            limiter,
+            queue,
+            waker: None,
+        }
+    }
+
+    /// Check if the rate-limiter would allow a request through.
+    fn check(&mut self) -> Result<(), ()> {
+        match self.limiter.check() {
+            Ok(()) => Ok(()),
+            Err(nc) => {
+                self.delay.reset(nc.earliest_possible());
+                Err(())
+            }
+        }
+    }
+}
+
+impl<'a, A: Algorithm<Instant>, C: Clock<Instant = Instant>> Future for FairRatelimit<'a, A, C>
+where
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match &self.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            Some(waker) => {
+                self.queue.update(waker, cx.waker().clone());
+                self.waker = Some(cx.waker().clone());
+            }
+            None => {
+                self.queue.enqueue(cx.waker().clone());
+                self.waker = Some(cx.waker().clone());
+            }
+        }
+        if !self.queue.is_head(cx.waker()) {
+            return Poll::Pending;
+        }
+        loop {
+            match self.check() {
+                Ok(()) => {
+                    self.queue.pop_and_wake_next();
+                    self.waker = None;
+                    return Poll::Ready(());
+                }
+                Err(()) => match self.delay.as_mut().poll(cx) {
+                    Poll::Ready(_) => continue,
+                    Poll::Pending => return Poll::Pending,
+                },
+            }
+        }
+    }
+}
+
+impl<'a, A: Algorithm<Instant>, C: Clock<Instant = Instant>> Drop for FairRatelimit<'a, A, C>
+where
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    fn drop(&mut self) {
+        // If we're dropped (e.g. the future was cancelled) while still
+        // queued, make sure we don't leave a dangling head-of-line
+        // waker that nobody will ever pop.
+        if let Some(waker) = self.waker.take() {
+            self.queue.remove(&waker);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, poll};
+    use ratelimit_meter::{DirectRateLimiter, LeakyBucket};
+    use std::{num::NonZeroU32, time::Duration};
+
+    #[test]
+    fn grants_the_oldest_waiter_first() {
+        block_on(async {
+            let mut lim = DirectRateLimiter::<LeakyBucket>::new(
+                NonZeroU32::new(1).unwrap(),
+                Duration::from_millis(50),
+            );
+            let queue = FairQueue::new();
+
+            // Drain the one currently-available cell so every
+            // `FairRatelimit` below starts out negative and has to queue.
+            lim.check().unwrap();
+
+            let mut lim_a = lim.clone();
+            let mut lim_b = lim.clone();
+            let mut a = Box::pin(FairRatelimit::new(&mut lim_a, queue.clone()));
+            let mut b = Box::pin(FairRatelimit::new(&mut lim_b, queue.clone()));
+
+            // `a` joins the queue first, then `b`.
+            assert!(poll!(a.as_mut()).is_pending());
+            assert!(poll!(b.as_mut()).is_pending());
+
+            // Once a cell frees up, `a` (the longer-waiting future) must be
+            // the one to proceed, even though `b` is polled here too.
+            a.await;
+            assert!(poll!(b.as_mut()).is_pending());
+            b.await;
+        });
+    }
+
+    #[test]
+    fn dropping_a_queued_waiter_lets_the_next_one_through() {
+        block_on(async {
+            let mut lim = DirectRateLimiter::<LeakyBucket>::new(
+                NonZeroU32::new(1).unwrap(),
+                Duration::from_millis(50),
+            );
+            let queue = FairQueue::new();
+            lim.check().unwrap();
+
+            let mut lim_a = lim.clone();
+            let mut lim_b = lim.clone();
+            let mut abandoned = Box::pin(FairRatelimit::new(&mut lim_a, queue.clone()));
+            let mut b = Box::pin(FairRatelimit::new(&mut lim_b, queue.clone()));
+
+            assert!(poll!(abandoned.as_mut()).is_pending());
+            assert!(poll!(b.as_mut()).is_pending());
+
+            // `abandoned` was ahead of `b` in the queue; dropping it must
+            // deregister its head-of-line waker so `b` isn't left waiting
+            // behind a future that will never finish.
+            drop(abandoned);
+            b.await;
+        });
+    }
+}
@@ -0,0 +1,218 @@
+//! A concurrency-limiting gate, for capping how many guarded operations
+//! may be in flight at once.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+struct State {
+    available: usize,
+    waiters: VecDeque<Waker>,
+}
+
+/// Caps the number of concurrently in-flight operations at `max`.
+///
+/// Unlike [`Ratelimit`](crate::Ratelimit), which bounds how often work
+/// may *start*, a `GateKeeper` bounds how many guarded operations may be
+/// outstanding *at once*. The two can be layered so a pipeline is
+/// bounded both by requests-per-second and by max concurrency.
+#[derive(Clone)]
+pub struct GateKeeper {
+    state: Arc<Mutex<State>>,
+}
+
+impl GateKeeper {
+    /// Creates a gate that allows at most `max` permits to be held at
+    /// once.
+    pub fn new(max: usize) -> Self {
+        GateKeeper {
+            state: Arc::new(Mutex::new(State {
+                available: max,
+                waiters: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Waits for a free permit, resolving to an RAII guard that releases
+    /// it (and wakes the next waiter, if any) when dropped.
+    pub fn acquire(&self) -> Acquire<'_> {
+        Acquire {
+            gate: self,
+            waker: None,
+        }
+    }
+}
+
+/// Future returned by [`GateKeeper::acquire`].
+pub struct Acquire<'a> {
+    gate: &'a GateKeeper,
+    waker: Option<Waker>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Permit;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.gate.state.lock().unwrap();
+        if self.waker.is_none() {
+            // First poll: only take the fast path if nobody is already
+            // ahead of us in the queue. Otherwise a brand-new `Acquire`
+            // could steal a freshly freed permit out from under a
+            // waiter that's been queued longer; join the queue instead,
+            // same as every other hand-rolled waiter queue in this
+            // crate.
+            if state.waiters.is_empty() && state.available > 0 {
+                state.available -= 1;
+                return Poll::Ready(Permit {
+                    state: self.gate.state.clone(),
+                });
+            }
+            state.waiters.push_back(cx.waker().clone());
+            self.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        // We're already queued; refresh our entry in place if we're
+        // polled with a different waker (e.g. after being moved between
+        // tasks), rather than re-enqueuing, which would leave a stale
+        // duplicate entry behind.
+        let waker = self.waker.as_ref().unwrap();
+        if !waker.will_wake(cx.waker()) {
+            if let Some(pos) = state.waiters.iter().position(|w| w.will_wake(waker)) {
+                state.waiters[pos] = cx.waker().clone();
+            }
+            self.waker = Some(cx.waker().clone());
+        }
+        let is_head = state
+            .waiters
+            .front()
+            .is_some_and(|front| front.will_wake(cx.waker()));
+        if is_head && state.available > 0 {
+            state.available -= 1;
+            state.waiters.pop_front();
+            self.waker = None;
+            return Poll::Ready(Permit {
+                state: self.gate.state.clone(),
+            });
+        }
+        Poll::Pending
+    }
+}
+
+impl<'a> Drop for Acquire<'a> {
+    fn drop(&mut self) {
+        // If we're dropped while still queued (e.g. the future was
+        // cancelled), deregister so a freed permit isn't handed to a
+        // waker nobody is polling anymore.
+        if let Some(waker) = self.waker.take() {
+            let mut state = self.gate.state.lock().unwrap();
+            if let Some(pos) = state.waiters.iter().position(|w| w.will_wake(&waker)) {
+                state.waiters.remove(pos);
+                // If we were the head and a permit is already free, its
+                // wake-up went to us; pass it on to the new head.
+                if pos == 0 && state.available > 0 {
+                    if let Some(next) = state.waiters.front() {
+                        next.wake_by_ref();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// An RAII permit acquired from a [`GateKeeper`]. Restores the permit
+/// (and wakes the next waiter) when dropped.
+pub struct Permit {
+    state: Arc<Mutex<State>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.available += 1;
+        // Wake (but don't dequeue) the front waiter: it stays at the
+        // head of the line until it's actually re-polled and claims the
+        // permit, so a brand-new `Acquire` polled in the meantime still
+        // sees it as the head and can't jump ahead of it.
+        if let Some(waker) = state.waiters.front() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, poll};
+
+    #[test]
+    fn queues_waiters_fifo_without_duplicate_wakes() {
+        block_on(async {
+            let gate = GateKeeper::new(1);
+            let first = gate.acquire().await;
+
+            let mut second = Box::pin(gate.acquire());
+            let mut third = Box::pin(gate.acquire());
+            // Neither waiter can proceed while `first` holds the only permit.
+            assert!(poll!(second.as_mut()).is_pending());
+            assert!(poll!(third.as_mut()).is_pending());
+
+            drop(first);
+            // `second` was queued first, so it gets the freed permit...
+            let second_permit = second.await;
+            // ...and `third` must still be waiting behind it, not woken by a
+            // stale duplicate queue entry left over from re-polling `second`.
+            assert!(poll!(third.as_mut()).is_pending());
+
+            drop(second_permit);
+            third.await;
+        });
+    }
+
+    #[test]
+    fn a_fresh_acquire_cannot_jump_a_queued_waiter() {
+        block_on(async {
+            let gate = GateKeeper::new(1);
+            let first = gate.acquire().await;
+
+            let mut queued = Box::pin(gate.acquire());
+            assert!(poll!(queued.as_mut()).is_pending());
+
+            drop(first);
+
+            // A brand-new `Acquire`, polled for the first time after the
+            // permit freed up but before `queued` is re-polled, must not
+            // steal it out of FIFO order.
+            let mut newcomer = Box::pin(gate.acquire());
+            assert!(poll!(newcomer.as_mut()).is_pending());
+
+            let queued_permit = queued.await;
+            assert!(poll!(newcomer.as_mut()).is_pending());
+
+            drop(queued_permit);
+            newcomer.await;
+        });
+    }
+
+    #[test]
+    fn dropping_a_pending_acquire_deregisters_it() {
+        block_on(async {
+            let gate = GateKeeper::new(1);
+            let first = gate.acquire().await;
+
+            let mut abandoned = Box::pin(gate.acquire());
+            assert!(poll!(abandoned.as_mut()).is_pending());
+            drop(abandoned);
+
+            let mut next = Box::pin(gate.acquire());
+            assert!(poll!(next.as_mut()).is_pending());
+            drop(first);
+            // The abandoned waiter deregistered itself on drop, so the freed
+            // permit goes to `next` instead of being wasted on a dead waker.
+            next.await;
+        });
+    }
+}
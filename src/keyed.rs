@@ -0,0 +1,137 @@
+//! Rate limiting futures for `ratelimit_meter`'s keyed rate limiters.
+
+use std::{
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use futures_timer::Delay;
+use ratelimit_meter::{
+    algorithms::{Algorithm, KeyableRateLimitState},
+    clock::Clock,
+    KeyedRateLimiter, NonConformance,
+};
+
+/// The rate-limiter as a future, for a single key of a
+/// [`KeyedRateLimiter`].
+///
+/// This is the keyed counterpart to [`Ratelimit`](crate::Ratelimit), for
+/// callers who need to rate-limit per key (e.g. per-IP or per-user)
+/// rather than globally.
+pub struct KeyedRatelimit<'a, K, A, C>
+where
+    K: Eq + Hash + Clone + Unpin,
+    A: Algorithm<Instant>,
+    A::BucketState: KeyableRateLimitState<A, Instant>,
+    C: Clock<Instant = Instant>,
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    delay: Pin<Box<Delay>>,
+    limiter: &'a mut KeyedRateLimiter<K, A, C>,
+    key: K,
+    first_time: bool,
+}
+
+impl<'a, K, A, C> KeyedRatelimit<'a, K, A, C>
+where
+    K: Eq + Hash + Clone + Unpin,
+    A: Algorithm<Instant>,
+    A::BucketState: KeyableRateLimitState<A, Instant>,
+    C: Clock<Instant = Instant>,
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    /// Check if the rate-limiter would allow `self.key` through.
+    fn check(&mut self) -> Result<(), ()> {
+        match self.limiter.check(self.key.clone()) {
+            Ok(()) => Ok(()),
+            Err(nc) => {
+                self.delay.reset(nc.earliest_possible());
+                Err(())
+            }
+        }
+    }
+
+    /// Creates a new future that resolves successfully as soon as the
+    /// keyed rate limiter allows `key` through.
+    pub fn new(limiter: &'a mut KeyedRateLimiter<K, A, C>, key: K) -> Self {
+        KeyedRatelimit {
+            delay: Box::pin(Delay::new(Default::default())),
+            first_time: true,
+            limiter,
+            key,
+        }
+    }
+}
+
+impl<'a, K, A, C> Future for KeyedRatelimit<'a, K, A, C>
+where
+    K: Eq + Hash + Clone + Unpin,
+    A: Algorithm<Instant>,
+    A::BucketState: KeyableRateLimitState<A, Instant>,
+    C: Clock<Instant = Instant>,
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if self.first_time {
+            // First time we run, let's check the rate-limiter and set
+            // up a delay if we can't proceed:
+            self.first_time = false;
+            if self.check().is_ok() {
+                return Poll::Ready(());
+            }
+        }
+        match self.delay.as_mut().poll(cx) {
+            // Timer says we should check the rate-limiter again, do
+            // it and reset the delay otherwise.
+            Poll::Ready(_) => match self.check() {
+                Ok(_) => Poll::Ready(()),
+                Err(_) => {
+                    // `check` just reset `self.delay`, which drops its
+                    // old waker registration; poll it again so we're
+                    // woken when it elapses instead of hanging forever.
+                    let _ = self.delay.as_mut().poll(cx);
+                    Poll::Pending
+                }
+            },
+
+            // timer isn't yet ready, let's wait:
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use ratelimit_meter::GCRA;
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    #[test]
+    fn each_key_gets_its_own_budget() {
+        block_on(async {
+            let mut limiter =
+                KeyedRateLimiter::<&str, GCRA>::new(NonZeroU32::new(1).unwrap(), Duration::from_secs(5));
+
+            // The first cell for "a" is admitted immediately...
+            KeyedRatelimit::new(&mut limiter, "a").await;
+            // ...and so is the first cell for the unrelated key "b",
+            // because each key tracks its own rate independently.
+            KeyedRatelimit::new(&mut limiter, "b").await;
+
+            // GCRA's burst allowance is capacity + 1, so a second cell
+            // for "a" is still conforming immediately...
+            assert!(limiter.check("a").is_ok());
+            // ...but the third is not, and would have to wait out the
+            // 5 second window; just check the limiter directly instead
+            // of waiting out the delay.
+            assert!(limiter.check("a").is_err());
+        });
+    }
+}
@@ -50,12 +50,99 @@ use std::{
     future::Future,
     pin::Pin,
     task::{Context, Poll},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use futures_timer::Delay;
+use rand::Rng;
 use ratelimit_meter::{algorithms::Algorithm, clock::Clock, DirectRateLimiter, NonConformance};
 
+pub mod adaptive;
+pub mod fair;
+pub mod gate;
+pub mod keyed;
+pub mod service;
+pub mod stream;
+pub use adaptive::{AdaptiveRatelimit, Outcome};
+pub use fair::{FairQueue, FairRatelimit};
+pub use gate::GateKeeper;
+pub use keyed::KeyedRatelimit;
+pub use service::RateLimit;
+pub use stream::StreamRateLimitExt;
+
+/// A random jitter to add to the delay before a rate-limited future is
+/// retried, so that futures waiting on the same limiter don't all wake
+/// up and stampede it at once.
+///
+/// The actual delay added is `min + rand(0..=(max - min))`.
+#[derive(Debug, Clone, Copy)]
+pub struct Jitter {
+    min: Duration,
+    max: Duration,
+}
+
+impl Jitter {
+    /// Creates a new `Jitter` that adds somewhere between `min` and `max`
+    /// to the computed delay.
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Jitter { min, max }
+    }
+
+    fn get(&self) -> Duration {
+        let range = self.max.saturating_sub(self.min).as_nanos() as u64;
+        let extra = if range == 0 {
+            0
+        } else {
+            rand::thread_rng().gen_range(0..=range)
+        };
+        self.min + Duration::from_nanos(extra)
+    }
+}
+
+/// Drives the common "check the limiter, and if it says no, wait out a
+/// delay seeded from its `earliest_possible()` before checking again"
+/// state machine shared by the various futures in this crate.
+///
+/// `check` should consult whatever limiter it closes over and, on a
+/// negative decision, reset `delay` from the decision's
+/// `earliest_possible()` (adding jitter or anything else the caller
+/// needs) before returning `Err(())`.
+pub(crate) fn poll_check_delay<F>(
+    delay: &mut Pin<Box<Delay>>,
+    first_time: &mut bool,
+    cx: &mut Context,
+    mut check: F,
+) -> Poll<()>
+where
+    F: FnMut(&mut Pin<Box<Delay>>) -> Result<(), ()>,
+{
+    if *first_time {
+        // First time we run, let's check the rate-limiter and set up a
+        // delay if we can't proceed:
+        *first_time = false;
+        if check(delay).is_ok() {
+            return Poll::Ready(());
+        }
+    }
+    match delay.as_mut().poll(cx) {
+        // Timer says we should check the rate-limiter again, do it and
+        // reset the delay otherwise.
+        Poll::Ready(_) => match check(delay) {
+            Ok(()) => Poll::Ready(()),
+            Err(()) => {
+                // `check` just reset `delay`, which drops its old waker
+                // registration; poll it again so we're woken when it
+                // elapses instead of hanging forever.
+                let _ = delay.as_mut().poll(cx);
+                Poll::Pending
+            }
+        },
+
+        // timer isn't yet ready, let's wait:
+        Poll::Pending => Poll::Pending,
+    }
+}
+
 /// The rate-limiter as a future.
 pub struct Ratelimit<'a, A: Algorithm<Instant>, C: Clock<Instant = Instant>>
 where
@@ -64,6 +151,7 @@ where
     delay: Pin<Box<Delay>>,
     limiter: &'a mut DirectRateLimiter<A, C>,
     first_time: bool,
+    jitter: Option<Jitter>,
 }
 
 impl<'a, A: Algorithm<Instant>, C: Clock<Instant = Instant>> Ratelimit<'a, A, C>
@@ -75,7 +163,10 @@ where
         match self.limiter.check() {
             Ok(()) => Ok(()),
             Err(nc) => {
-                let earliest = nc.earliest_possible();
+                let mut earliest = nc.earliest_possible();
+                if let Some(jitter) = &self.jitter {
+                    earliest += jitter.get();
+                }
                 self.delay.reset(earliest);
                 Err(())
             }
@@ -89,6 +180,20 @@ where
             delay: Box::pin(Delay::new(Default::default())),
             first_time: true,
             limiter,
+            jitter: None,
+        }
+    }
+
+    /// Creates a new future like [`Ratelimit::new`], but adds `jitter` to
+    /// the delay on every negative decision. This spreads out the
+    /// retries of futures that share a limiter, instead of having them
+    /// all wake up and recheck the limiter at exactly the same instant.
+    pub fn new_with_jitter(limiter: &'a mut DirectRateLimiter<A, C>, jitter: Jitter) -> Self {
+        Ratelimit {
+            delay: Box::pin(Delay::new(Default::default())),
+            first_time: true,
+            limiter,
+            jitter: Some(jitter),
         }
     }
 }
@@ -124,3 +229,36 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use ratelimit_meter::LeakyBucket;
+    use std::num::NonZeroU32;
+
+    #[test]
+    fn jitter_get_is_within_bounds() {
+        let jitter = Jitter::new(Duration::from_millis(5), Duration::from_millis(15));
+        for _ in 0..100 {
+            let delay = jitter.get();
+            assert!(delay >= Duration::from_millis(5));
+            assert!(delay <= Duration::from_millis(15));
+        }
+    }
+
+    #[test]
+    fn jitter_get_with_equal_bounds_is_exact() {
+        let jitter = Jitter::new(Duration::from_millis(10), Duration::from_millis(10));
+        assert_eq!(jitter.get(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn new_with_jitter_still_resolves_once_the_limiter_admits_a_cell() {
+        let mut lim = DirectRateLimiter::<LeakyBucket>::per_second(NonZeroU32::new(1000).unwrap());
+        block_on(Ratelimit::new_with_jitter(
+            &mut lim,
+            Jitter::new(Duration::from_millis(1), Duration::from_millis(5)),
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+//! A `poll_ready`/dispatch wrapper around [`DirectRateLimiter`], for
+//! integrating with `tower::Service`-style readiness contracts.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use futures_timer::Delay;
+use ratelimit_meter::{algorithms::Algorithm, clock::Clock, DirectRateLimiter, NonConformance};
+
+/// Wraps a [`DirectRateLimiter`] in a `poll_ready` state machine, so it
+/// can gate a `tower::Service::poll_ready` implementation instead of
+/// being awaited as a one-shot future.
+///
+/// A cell is admitted from the limiter the moment `poll_ready` reports
+/// [`Poll::Ready`], but it is only *spent* once [`call`](Self::call) is
+/// invoked. Polling again before `call` returns the same reservation
+/// without debiting another cell, so checking readiness without
+/// following through with a dispatch (e.g. because a `select!` picked a
+/// different branch) doesn't silently burn through the rate budget.
+pub struct RateLimit<A, C>
+where
+    A: Algorithm<Instant>,
+    C: Clock<Instant = Instant>,
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    limiter: DirectRateLimiter<A, C>,
+    delay: Pin<Box<Delay>>,
+    reserved: bool,
+}
+
+impl<A, C> RateLimit<A, C>
+where
+    A: Algorithm<Instant>,
+    C: Clock<Instant = Instant>,
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    /// Wraps `limiter` for use via `poll_ready`.
+    pub fn new(limiter: DirectRateLimiter<A, C>) -> Self {
+        RateLimit {
+            limiter,
+            delay: Box::pin(Delay::new(Default::default())),
+            reserved: false,
+        }
+    }
+
+    /// Returns `Poll::Ready(())` once the limiter admits a cell,
+    /// registering the waker against an internal delay seeded from
+    /// [`NonConformance::earliest_possible`] otherwise.
+    ///
+    /// Once ready, the admitted cell is held in reserve until
+    /// [`call`](Self::call) spends it: further `poll_ready` calls
+    /// return `Ready` immediately instead of checking the limiter
+    /// again, so a reservation that's never dispatched isn't wasted.
+    pub fn poll_ready(&mut self, cx: &mut Context) -> Poll<()> {
+        if self.reserved {
+            return Poll::Ready(());
+        }
+        loop {
+            match self.limiter.check() {
+                Ok(()) => {
+                    self.reserved = true;
+                    return Poll::Ready(());
+                }
+                Err(nc) => self.delay.as_mut().reset(nc.earliest_possible()),
+            }
+            match self.delay.as_mut().poll(cx) {
+                Poll::Ready(_) => continue,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Spends the reservation granted by the last `Ready` from
+    /// [`poll_ready`](Self::poll_ready), matching the moment
+    /// `tower::Service::call` actually dispatches work. The next
+    /// `poll_ready` call will check the limiter for a fresh cell.
+    ///
+    /// Call this only once work has actually been dispatched for the
+    /// current reservation; if it's never called, the reserved cell
+    /// simply stays available for the next `poll_ready`.
+    pub fn call(&mut self) {
+        self.reserved = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker;
+    use ratelimit_meter::LeakyBucket;
+    use std::{num::NonZeroU32, time::Duration};
+
+    #[test]
+    fn unconsumed_reservation_is_not_rechecked_against_the_limiter() {
+        let mut rl = RateLimit::new(DirectRateLimiter::<LeakyBucket>::new(
+            NonZeroU32::new(1).unwrap(),
+            Duration::from_secs(60),
+        ));
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        assert!(rl.poll_ready(&mut cx).is_ready());
+        // Without calling `call`, the single available cell should
+        // still be reserved for us: polling again must not debit a
+        // second cell from the (now-exhausted) limiter.
+        assert!(rl.poll_ready(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn call_spends_the_reservation_so_the_next_one_is_rechecked() {
+        let mut rl = RateLimit::new(DirectRateLimiter::<LeakyBucket>::new(
+            NonZeroU32::new(1).unwrap(),
+            Duration::from_secs(60),
+        ));
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        assert!(rl.poll_ready(&mut cx).is_ready());
+        rl.call();
+
+        // The single cell per minute was already spent by the dispatch
+        // above, so the next readiness check has to wait.
+        assert!(rl.poll_ready(&mut cx).is_pending());
+    }
+}
@@ -0,0 +1,162 @@
+//! A `Stream` adaptor that throttles item production to a shared
+//! rate limiter.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use futures::Stream;
+use futures_timer::Delay;
+use ratelimit_meter::{algorithms::Algorithm, clock::Clock, DirectRateLimiter, NonConformance};
+
+/// Extension trait that adds [`ratelimit`](StreamRateLimitExt::ratelimit)
+/// to all `Stream`s.
+pub trait StreamRateLimitExt: Stream + Sized {
+    /// Throttles this stream so that it yields items no faster than
+    /// `limiter` allows, e.g. `some_stream.ratelimit(&mut lim)`.
+    ///
+    /// Only one item is buffered at a time: the underlying stream is
+    /// polled, its item held, and the limiter consulted (with a delay
+    /// in between attempts) until it is allowed through.
+    fn ratelimit<A, C>(
+        self,
+        limiter: &mut DirectRateLimiter<A, C>,
+    ) -> RatelimitedStream<'_, Self, A, C>
+    where
+        Self::Item: Unpin,
+        A: Algorithm<Instant>,
+        C: Clock<Instant = Instant>,
+        <A as Algorithm>::NegativeDecision: NonConformance,
+    {
+        RatelimitedStream {
+            inner: Box::pin(self),
+            limiter,
+            delay: Box::pin(Delay::new(Default::default())),
+            pending: None,
+        }
+    }
+}
+
+impl<S: Stream + Sized> StreamRateLimitExt for S {}
+
+/// A stream that yields the items of an underlying stream no faster
+/// than a shared [`DirectRateLimiter`] allows.
+///
+/// Created by [`StreamRateLimitExt::ratelimit`].
+pub struct RatelimitedStream<'a, S, A, C>
+where
+    S: Stream,
+    S::Item: Unpin,
+    A: Algorithm<Instant>,
+    C: Clock<Instant = Instant>,
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    inner: Pin<Box<S>>,
+    limiter: &'a mut DirectRateLimiter<A, C>,
+    delay: Pin<Box<Delay>>,
+    pending: Option<S::Item>,
+}
+
+impl<'a, S, A, C> Stream for RatelimitedStream<'a, S, A, C>
+where
+    S: Stream,
+    S::Item: Unpin,
+    A: Algorithm<Instant>,
+    C: Clock<Instant = Instant>,
+    <A as Algorithm>::NegativeDecision: NonConformance,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            if self.pending.is_none() {
+                match self.inner.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(item)) => self.pending = Some(item),
+                    Poll::Ready(None) => return Poll::Ready(None),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            match self.limiter.check() {
+                Ok(()) => return Poll::Ready(self.pending.take()),
+                Err(nc) => self.delay.as_mut().reset(nc.earliest_possible()),
+            }
+
+            match self.delay.as_mut().poll(cx) {
+                Poll::Ready(_) => continue,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, poll, stream, StreamExt};
+    use ratelimit_meter::LeakyBucket;
+    use std::{num::NonZeroU32, time::Duration};
+
+    #[test]
+    fn yields_every_item_of_the_underlying_stream_in_order() {
+        block_on(async {
+            let mut lim = DirectRateLimiter::<LeakyBucket>::per_second(NonZeroU32::new(1000).unwrap());
+            let items: Vec<i32> = stream::iter(vec![1, 2, 3]).ratelimit(&mut lim).collect().await;
+            assert_eq!(items, vec![1, 2, 3]);
+        });
+    }
+
+    #[test]
+    fn only_one_item_is_buffered_while_waiting_on_the_limiter() {
+        use std::{cell::Cell, collections::VecDeque, rc::Rc};
+
+        // A stream that counts how many times it's been drawn from, so
+        // we can confirm `RatelimitedStream` doesn't pull a second item
+        // out of it while still holding the first one back.
+        struct CountingStream {
+            items: VecDeque<i32>,
+            draws: Rc<Cell<u32>>,
+        }
+
+        impl Stream for CountingStream {
+            type Item = i32;
+
+            fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<i32>> {
+                self.draws.set(self.draws.get() + 1);
+                Poll::Ready(self.items.pop_front())
+            }
+        }
+
+        block_on(async {
+            let mut lim = DirectRateLimiter::<LeakyBucket>::new(
+                NonZeroU32::new(1).unwrap(),
+                Duration::from_millis(50),
+            );
+            // Drain the single available cell so the first item has to
+            // wait out a delay before being yielded.
+            lim.check().unwrap();
+
+            let draws = Rc::new(Cell::new(0));
+            let inner = CountingStream {
+                items: VecDeque::from(vec![1, 2]),
+                draws: draws.clone(),
+            };
+            let mut limited = inner.ratelimit(&mut lim);
+
+            let mut first = limited.next();
+            // The limiter has nothing left to give, so the first poll
+            // must actually wait instead of resolving straight away.
+            assert!(poll!(&mut first).is_pending());
+            assert_eq!(first.await, Some(1));
+            // Only the one item that was actually yielded should have
+            // been drawn from the inner stream so far.
+            assert_eq!(draws.get(), 1);
+
+            assert_eq!(limited.next().await, Some(2));
+            assert_eq!(draws.get(), 2);
+        });
+    }
+}